@@ -0,0 +1,295 @@
+//! Lightweight persistence of sweep progress.
+//!
+//! A `FullSync` or scrub sweep over a large device can take hours. Without
+//! this, a node restarted mid-sweep (e.g. during a rolling upgrade) has to
+//! re-scan objects it already covered. Progress is checkpointed to small
+//! sidecar records on the local device itself, addressed by lump ids
+//! reserved outside the space ever produced by object-version-derived lump
+//! ids (those only ever use the low 64 bits of a 128-bit lump id).
+use cannyls::device::DeviceHandle;
+use cannyls::lump::{Lump, LumpId};
+use futures::Future;
+use libfrugalos::entity::object::ObjectVersion;
+use std::collections::BTreeMap;
+
+use Error;
+
+const FULL_SYNC_CHECKPOINT_LUMP_ID: u128 = (1 << 127) | 1;
+const SCRUB_CHECKPOINT_LUMP_ID: u128 = (1 << 127) | 2;
+const DIGEST_CATALOGUE_LUMP_ID: u128 = (1 << 127) | 3;
+
+/// Whether `lump_id` lives in the range reserved by this module, rather than
+/// the range `ObjectVersion`-derived lump ids are drawn from (the low 64
+/// bits of a 128-bit lump id; see the module docs). Callers that enumerate
+/// every lump on a device (e.g. a scrub sweep) must skip these, or they will
+/// misinterpret a checkpoint record as a corrupt object.
+pub(crate) fn is_reserved_lump_id(lump_id: LumpId) -> bool {
+    u128::from(lump_id) & (1 << 127) != 0
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut array = [0u8; 8];
+    array.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_be_bytes(array)
+}
+
+/// The durable progress of a `FullSync` sweep.
+///
+/// `next_commit` is the sweep's fixed boundary (the same value passed to
+/// `FullSync::new`), not an in-sweep scan cursor — `full_sync::FullSync`
+/// exposes no such cursor to the synchronizer in this tree, and scanning
+/// from a boundary is not the same as resuming a scan already in progress.
+/// This type is therefore loaded at startup purely to report progress (see
+/// `synchronizer::Synchronizer::load_checkpoints`); nothing currently reads
+/// it back to skip work. An actual resume would require `FullSync` itself
+/// to expose a resumable constructor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FullSyncCheckpoint {
+    pub next_commit: ObjectVersion,
+    pub remaining: u64,
+}
+impl FullSyncCheckpoint {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.next_commit.0.to_be_bytes());
+        bytes.extend_from_slice(&self.remaining.to_be_bytes());
+        bytes
+    }
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 16 {
+            return None;
+        }
+        Some(FullSyncCheckpoint {
+            next_commit: ObjectVersion(read_u64(bytes, 0)),
+            remaining: read_u64(bytes, 8),
+        })
+    }
+}
+
+/// The durable progress of a scrub sweep: how many of the objects listed at
+/// sweep start have already been verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScrubCheckpoint {
+    pub position: u64,
+    pub remaining: u64,
+}
+impl ScrubCheckpoint {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.position.to_be_bytes());
+        bytes.extend_from_slice(&self.remaining.to_be_bytes());
+        bytes
+    }
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 16 {
+            return None;
+        }
+        Some(ScrubCheckpoint {
+            position: read_u64(bytes, 0),
+            remaining: read_u64(bytes, 8),
+        })
+    }
+}
+
+fn save(
+    device: &DeviceHandle,
+    lump_id: u128,
+    bytes: Vec<u8>,
+) -> impl Future<Item = (), Error = Error> {
+    device
+        .request()
+        .put(LumpId::new(lump_id), Lump::new(bytes))
+        .map(|_| ())
+        .map_err(|e| track!(Error::from(e)))
+}
+
+fn load(device: &DeviceHandle, lump_id: u128) -> impl Future<Item = Option<Vec<u8>>, Error = Error> {
+    device
+        .request()
+        .get(LumpId::new(lump_id))
+        .map(|lump| lump.map(|lump| lump.as_bytes().to_vec()))
+        .map_err(|e| track!(Error::from(e)))
+}
+
+fn clear(device: &DeviceHandle, lump_id: u128) -> impl Future<Item = (), Error = Error> {
+    device
+        .request()
+        .delete(LumpId::new(lump_id))
+        .map(|_| ())
+        .map_err(|e| track!(Error::from(e)))
+}
+
+pub fn save_full_sync_checkpoint(
+    device: &DeviceHandle,
+    checkpoint: FullSyncCheckpoint,
+) -> impl Future<Item = (), Error = Error> {
+    save(device, FULL_SYNC_CHECKPOINT_LUMP_ID, checkpoint.to_bytes())
+}
+
+pub fn load_full_sync_checkpoint(
+    device: &DeviceHandle,
+) -> impl Future<Item = Option<FullSyncCheckpoint>, Error = Error> {
+    load(device, FULL_SYNC_CHECKPOINT_LUMP_ID)
+        .map(|bytes| bytes.and_then(|b| FullSyncCheckpoint::from_bytes(&b)))
+}
+
+pub fn clear_full_sync_checkpoint(device: &DeviceHandle) -> impl Future<Item = (), Error = Error> {
+    clear(device, FULL_SYNC_CHECKPOINT_LUMP_ID)
+}
+
+pub fn save_scrub_checkpoint(
+    device: &DeviceHandle,
+    checkpoint: ScrubCheckpoint,
+) -> impl Future<Item = (), Error = Error> {
+    save(device, SCRUB_CHECKPOINT_LUMP_ID, checkpoint.to_bytes())
+}
+
+pub fn load_scrub_checkpoint(
+    device: &DeviceHandle,
+) -> impl Future<Item = Option<ScrubCheckpoint>, Error = Error> {
+    load(device, SCRUB_CHECKPOINT_LUMP_ID)
+        .map(|bytes| bytes.and_then(|b| ScrubCheckpoint::from_bytes(&b)))
+}
+
+pub fn clear_scrub_checkpoint(device: &DeviceHandle) -> impl Future<Item = (), Error = Error> {
+    clear(device, SCRUB_CHECKPOINT_LUMP_ID)
+}
+
+/// A durable catalogue of the last checksum scrub observed for each object,
+/// so a later sweep can tell a bit-rotted object apart from one that has
+/// simply never been scrubbed before. Indexed by `ObjectVersion` rather than
+/// the lump id it is derived from, since the catalogue is compared against
+/// in-memory `ObjectVersion`s during a sweep (see `scrub::ScrubContent`).
+///
+/// This trades memory and device space (16 bytes per distinct object ever
+/// scrubbed) for the ability to detect corruption without a write-time
+/// checksum to compare against, which the write path (in `client::storage`)
+/// never records.
+fn digest_catalogue_to_bytes(digests: &BTreeMap<ObjectVersion, u64>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(8 + digests.len() * 16);
+    bytes.extend_from_slice(&(digests.len() as u64).to_be_bytes());
+    for (version, digest) in digests {
+        bytes.extend_from_slice(&version.0.to_be_bytes());
+        bytes.extend_from_slice(&digest.to_be_bytes());
+    }
+    bytes
+}
+
+fn digest_catalogue_from_bytes(bytes: &[u8]) -> Option<BTreeMap<ObjectVersion, u64>> {
+    if bytes.len() < 8 {
+        return None;
+    }
+    let count = read_u64(bytes, 0) as usize;
+    let body_len = bytes.len() - 8;
+    if body_len % 16 != 0 || body_len / 16 != count {
+        return None;
+    }
+    let mut digests = BTreeMap::new();
+    for i in 0..count {
+        let offset = 8 + i * 16;
+        let version = ObjectVersion(read_u64(bytes, offset));
+        let digest = read_u64(bytes, offset + 8);
+        digests.insert(version, digest);
+    }
+    Some(digests)
+}
+
+pub fn save_digest_catalogue(
+    device: &DeviceHandle,
+    digests: &BTreeMap<ObjectVersion, u64>,
+) -> impl Future<Item = (), Error = Error> {
+    save(
+        device,
+        DIGEST_CATALOGUE_LUMP_ID,
+        digest_catalogue_to_bytes(digests),
+    )
+}
+
+pub fn load_digest_catalogue(
+    device: &DeviceHandle,
+) -> impl Future<Item = BTreeMap<ObjectVersion, u64>, Error = Error> {
+    load(device, DIGEST_CATALOGUE_LUMP_ID)
+        .map(|bytes| bytes.and_then(|b| digest_catalogue_from_bytes(&b)).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_sync_checkpoint_round_trips() {
+        let checkpoint = FullSyncCheckpoint {
+            next_commit: ObjectVersion(42),
+            remaining: 7,
+        };
+        assert_eq!(
+            FullSyncCheckpoint::from_bytes(&checkpoint.to_bytes()),
+            Some(checkpoint)
+        );
+    }
+
+    #[test]
+    fn full_sync_checkpoint_rejects_malformed_bytes() {
+        assert_eq!(FullSyncCheckpoint::from_bytes(&[]), None);
+        assert_eq!(FullSyncCheckpoint::from_bytes(&[0u8; 15]), None);
+        assert_eq!(FullSyncCheckpoint::from_bytes(&[0u8; 17]), None);
+    }
+
+    #[test]
+    fn scrub_checkpoint_round_trips() {
+        let checkpoint = ScrubCheckpoint {
+            position: 123,
+            remaining: 456,
+        };
+        assert_eq!(
+            ScrubCheckpoint::from_bytes(&checkpoint.to_bytes()),
+            Some(checkpoint)
+        );
+    }
+
+    #[test]
+    fn scrub_checkpoint_rejects_malformed_bytes() {
+        assert_eq!(ScrubCheckpoint::from_bytes(&[]), None);
+        assert_eq!(ScrubCheckpoint::from_bytes(&[0u8; 15]), None);
+        assert_eq!(ScrubCheckpoint::from_bytes(&[0u8; 17]), None);
+    }
+
+    #[test]
+    fn digest_catalogue_round_trips() {
+        let mut digests = BTreeMap::new();
+        digests.insert(ObjectVersion(1), 0xdead_beef);
+        digests.insert(ObjectVersion(2), 0xcafe_babe);
+        assert_eq!(
+            digest_catalogue_from_bytes(&digest_catalogue_to_bytes(&digests)),
+            Some(digests)
+        );
+    }
+
+    #[test]
+    fn digest_catalogue_round_trips_when_empty() {
+        let digests = BTreeMap::new();
+        assert_eq!(
+            digest_catalogue_from_bytes(&digest_catalogue_to_bytes(&digests)),
+            Some(digests)
+        );
+    }
+
+    #[test]
+    fn digest_catalogue_rejects_malformed_bytes() {
+        assert_eq!(digest_catalogue_from_bytes(&[]), None);
+        assert_eq!(digest_catalogue_from_bytes(&[0u8; 7]), None);
+        // Claims one entry (16 bytes) but only supplies 8.
+        let mut truncated = 1u64.to_be_bytes().to_vec();
+        truncated.extend_from_slice(&[0u8; 8]);
+        assert_eq!(digest_catalogue_from_bytes(&truncated), None);
+    }
+
+    #[test]
+    fn digest_catalogue_rejects_huge_count_without_overflow() {
+        // A corrupted header claiming a count that would overflow `count * 16`
+        // must be rejected, not panic (debug) or wrap (release).
+        let mut bytes = (u64::MAX / 8).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&[0u8; 16]);
+        assert_eq!(digest_catalogue_from_bytes(&bytes), None);
+    }
+}
@@ -0,0 +1,454 @@
+//! Proactive background scrubbing of locally stored objects.
+//!
+//! Caveat: this only detects corruption that happens *between* two scrubs of
+//! the same object, not corruption already present before its first-ever
+//! scrub. The write path (in `client::storage`) never
+//! hands a checksum to compare against, so an object's first scrub simply
+//! records whatever bytes are currently on disk as the trusted baseline
+//! (`VerifyOutcome::Intact`) — it cannot tell "written correctly" apart from
+//! "already corrupted before we ever looked". A node that has never
+//! completed a scrub sweep, or an object scrubbed for the first time after
+//! it was already silently corrupted, gets no detection for that damage.
+use cannyls::device::DeviceHandle;
+use cannyls::lump::LumpId;
+use fibers::time::timer::{self, Timeout};
+use futures::{Async, Future, Poll};
+use libfrugalos::entity::object::ObjectVersion;
+use prometrics::metrics::{Counter, Gauge};
+use slog::Logger;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use checkpoint::{self, ScrubCheckpoint};
+use frugalos_raft::NodeId;
+use token_bucket::TokenBucket;
+use Error;
+
+// Mirrors `synchronizer::ESTIMATED_IO_UNIT_BYTES`: the amount of I/O a
+// single object read is assumed to cost when rate-limiting scrub.
+const ESTIMATED_IO_UNIT_BYTES: u64 = 1024 * 1024;
+
+/// Converts an `ObjectVersion` into the `LumpId` under which its content is
+/// stored on the local device.
+fn object_version_to_lump_id(version: ObjectVersion) -> LumpId {
+    LumpId::new(u128::from(version.0))
+}
+
+/// Converts a `LumpId` listed by the device back into the `ObjectVersion` it
+/// was stored under.
+fn lump_id_to_object_version(lump_id: LumpId) -> ObjectVersion {
+    ObjectVersion(u128::from(lump_id) as u64)
+}
+
+/// Records `digest` as `version`'s latest observed digest and decides
+/// whether that amounts to corruption, pulled out of `ScrubContent::verify`
+/// as a pure function so the decision itself (as opposed to the I/O around
+/// it) can be unit tested without a real `DeviceHandle`.
+fn record_digest(
+    digests: &mut BTreeMap<ObjectVersion, u64>,
+    version: ObjectVersion,
+    digest: u64,
+) -> VerifyOutcome {
+    match digests.insert(version, digest) {
+        Some(previous) if previous != digest => VerifyOutcome::Corrupted,
+        _ => VerifyOutcome::Intact,
+    }
+}
+
+/// A pure, dependency-free FNV-1a hash of an object's bytes, used as its
+/// content digest.
+///
+/// This is not a cryptographic hash; it only needs to catch accidental
+/// bit-rot, not a malicious actor, and FNV-1a is cheap enough to run over
+/// every scrubbed object without its own rate limit.
+fn checksum(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    })
+}
+
+/// The result of re-reading and re-checksumming a single object.
+#[derive(Debug, PartialEq, Eq)]
+enum VerifyOutcome {
+    /// The object's digest matches the one last recorded for it (or this is
+    /// the first time it has ever been scrubbed).
+    Intact,
+    /// The object was deleted out from under the sweep; not corruption.
+    Vanished,
+    /// The object's digest no longer matches the one recorded on a previous
+    /// scrub: its content has changed on disk without going through a write.
+    Corrupted,
+}
+
+type VerifyFuture = Box<Future<Item = VerifyOutcome, Error = Error> + Send + 'static>;
+type ListFuture = Box<Future<Item = Vec<LumpId>, Error = Error> + Send + 'static>;
+
+enum State {
+    /// Waiting for the initial listing of every lump stored on the device.
+    Listing(ListFuture),
+    /// Verifying the objects found by the listing, `step` at a time.
+    Verifying {
+        versions: Vec<ObjectVersion>,
+        position: usize,
+        current: Option<VerifyFuture>,
+    },
+}
+
+/// A long-lived, steppable sweep that re-reads every object stored on the
+/// local device and checks its integrity.
+///
+/// This works the same way as `FullSync`: it verifies at most `step`
+/// objects per `poll` call, so a sweep over a large device never blocks the
+/// synchronizer's event loop for long. Objects that fail verification are
+/// collected so that the caller can enqueue a repair for each of them.
+pub struct ScrubContent {
+    logger: Logger,
+    #[allow(dead_code)]
+    node_id: NodeId,
+    device: DeviceHandle,
+    step: u64,
+    state: State,
+    corrupted: Vec<ObjectVersion>,
+    scrub_count: Counter,
+    scrub_corrupted_objects: Counter,
+    scrub_remaining: Gauge,
+    io_limiter: Arc<Mutex<TokenBucket>>,
+    waiting: Option<Timeout>,
+    // How many objects from the start of the (re-)listed set were already
+    // verified in a previous run, per a loaded checkpoint. Applied once the
+    // listing completes; see `resume_from`.
+    resume_position: u64,
+    // The digest recorded for each object as of its last scrub, persisted
+    // alongside the checkpoints in `checkpoint.rs` so that corruption is
+    // still detectable across a restart. Shared (rather than owned) because
+    // `Synchronizer` persists it via `maybe_checkpoint` independently of this
+    // sweep's own lifetime.
+    object_digests: Arc<Mutex<BTreeMap<ObjectVersion, u64>>>,
+}
+impl ScrubContent {
+    /// Starts a new scrub sweep over every object currently on the device.
+    ///
+    /// If `resume_from` is `Some`, the sweep skips that many objects from
+    /// the start of the (freshly re-)listed set, picking up where a prior,
+    /// interrupted sweep left off. Because the listing is re-fetched, this
+    /// is only an approximation if objects were added or removed since the
+    /// checkpoint was taken.
+    pub fn new(
+        logger: &Logger,
+        node_id: NodeId,
+        device: &DeviceHandle,
+        scrub_count: Counter,
+        scrub_corrupted_objects: Counter,
+        scrub_remaining: Gauge,
+        step: u64,
+        io_limiter: Arc<Mutex<TokenBucket>>,
+        resume_from: Option<ScrubCheckpoint>,
+        object_digests: Arc<Mutex<BTreeMap<ObjectVersion, u64>>>,
+    ) -> Self {
+        let listing = device
+            .request()
+            .list()
+            .map_err(|e| track!(Error::from(e)));
+        ScrubContent {
+            logger: logger.clone(),
+            node_id,
+            device: device.clone(),
+            step,
+            state: State::Listing(Box::new(listing)),
+            corrupted: Vec::new(),
+            scrub_count,
+            scrub_corrupted_objects,
+            scrub_remaining,
+            io_limiter,
+            waiting: None,
+            resume_position: resume_from.map_or(0, |checkpoint| checkpoint.position),
+            object_digests,
+        }
+    }
+
+    /// Drains the list of objects found to be corrupted since the last call.
+    ///
+    /// This accumulates across `poll`, independent of whether `poll` itself
+    /// has returned `Ready` yet (a sweep over a large device can run for a
+    /// long time before it does). Callers must call this every tick the
+    /// scrub task is polled, not only once the sweep completes, or objects
+    /// found corrupted early in a long sweep sit unrepaired for its whole
+    /// duration — and are lost entirely if the sweep is then cancelled or
+    /// the process restarts.
+    pub fn take_corrupted_objects(&mut self) -> Vec<ObjectVersion> {
+        mem::replace(&mut self.corrupted, Vec::new())
+    }
+
+    /// A snapshot of how far this sweep has progressed, suitable for
+    /// persisting so a restart can resume from here.
+    pub fn checkpoint(&self) -> ScrubCheckpoint {
+        match self.state {
+            State::Listing(_) => ScrubCheckpoint {
+                position: self.resume_position,
+                remaining: 0,
+            },
+            State::Verifying {
+                ref versions,
+                position,
+                ..
+            } => ScrubCheckpoint {
+                position: position as u64,
+                remaining: (versions.len() - position) as u64,
+            },
+        }
+    }
+
+    /// Reads an object's stored bytes back off the device and reports
+    /// whether its content still matches the digest recorded for it on a
+    /// previous scrub.
+    ///
+    /// A read failure (`Err`, handled by the caller) is itself evidence of
+    /// corruption or unavailability. A successful read is further checked
+    /// against `object_digests`: the first time an object is scrubbed, its
+    /// digest is simply recorded as a baseline (`Intact`); on every later
+    /// scrub, a mismatch against that baseline means the bytes on disk
+    /// changed without going through a write, i.e. silent corruption.
+    fn verify(
+        device: &DeviceHandle,
+        version: ObjectVersion,
+        object_digests: Arc<Mutex<BTreeMap<ObjectVersion, u64>>>,
+    ) -> VerifyFuture {
+        let lump_id = object_version_to_lump_id(version);
+        let future = device
+            .request()
+            .get(lump_id)
+            .map(move |lump| match lump {
+                None => {
+                    // The object is gone; drop any digest recorded for it so
+                    // the catalogue doesn't grow without bound over objects
+                    // that no longer exist.
+                    object_digests.lock().unwrap().remove(&version);
+                    VerifyOutcome::Vanished
+                }
+                Some(lump) => {
+                    let digest = checksum(lump.as_bytes());
+                    let mut digests = object_digests.lock().unwrap();
+                    record_digest(&mut digests, version, digest)
+                }
+            })
+            .map_err(|e| track!(Error::from(e)));
+        Box::new(future)
+    }
+}
+impl Future for ScrubContent {
+    type Item = ();
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref mut waiting) = self.waiting {
+            match track!(waiting.poll().map_err(Error::from))? {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(()) => {}
+            }
+        }
+        self.waiting = None;
+
+        if let State::Listing(ref mut f) = self.state {
+            let lump_ids = match track!(f.poll())? {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(lump_ids) => lump_ids,
+            };
+            let versions: Vec<_> = lump_ids
+                .into_iter()
+                // Skip the checkpoint module's own sidecar records: they
+                // live outside the range `ObjectVersion`-derived lump ids
+                // are drawn from, but without this filter they'd truncate
+                // into bogus, perpetually-failing `ObjectVersion`s.
+                .filter(|lump_id| !checkpoint::is_reserved_lump_id(*lump_id))
+                .map(lump_id_to_object_version)
+                .collect();
+            let position = cmp::min(self.resume_position as usize, versions.len());
+            if position > 0 {
+                info!(
+                    self.logger,
+                    "Resuming scrub sweep from a checkpoint at position {}", position
+                );
+            }
+            self.scrub_remaining.set((versions.len() - position) as f64);
+            self.state = State::Verifying {
+                versions,
+                position,
+                current: None,
+            };
+        }
+
+        let device = self.device.clone();
+        let logger = self.logger.clone();
+        let (versions, position, current) = match self.state {
+            State::Verifying {
+                ref versions,
+                ref mut position,
+                ref mut current,
+            } => (versions, position, current),
+            State::Listing(_) => unreachable!(),
+        };
+
+        let mut checked = 0;
+        while checked < self.step {
+            if current.is_none() {
+                if *position >= versions.len() {
+                    self.scrub_remaining.set(0.0);
+                    return Ok(Async::Ready(()));
+                }
+                if let Some(wait) = self
+                    .io_limiter
+                    .lock()
+                    .unwrap()
+                    .try_consume(ESTIMATED_IO_UNIT_BYTES)
+                {
+                    self.waiting = Some(timer::timeout(wait));
+                    return Ok(Async::NotReady);
+                }
+                *current = Some(Self::verify(
+                    &device,
+                    versions[*position],
+                    Arc::clone(&self.object_digests),
+                ));
+            }
+            let version = versions[*position];
+            match track!(current.as_mut().expect("never fails").poll()) {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(outcome)) => {
+                    *current = None;
+                    *position += 1;
+                    checked += 1;
+                    self.scrub_count.increment();
+                    self.scrub_remaining.set((versions.len() - *position) as f64);
+                    match outcome {
+                        VerifyOutcome::Intact => {}
+                        VerifyOutcome::Vanished => {
+                            warn!(
+                                logger,
+                                "Object {:?} vanished during scrub; skipping", version
+                            );
+                        }
+                        VerifyOutcome::Corrupted => {
+                            warn!(
+                                logger,
+                                "Object {:?} failed its checksum during scrub", version
+                            );
+                            self.scrub_corrupted_objects.increment();
+                            self.corrupted.push(version);
+                        }
+                    }
+                }
+                Err(e) => {
+                    // A read failure is itself evidence of corruption (or of
+                    // a device that is temporarily unavailable); either way
+                    // the object is a repair candidate.
+                    warn!(
+                        logger,
+                        "Failed to read object {:?} during scrub: {}", version, e
+                    );
+                    *current = None;
+                    *position += 1;
+                    checked += 1;
+                    self.scrub_count.increment();
+                    self.scrub_corrupted_objects.increment();
+                    self.corrupted.push(version);
+                    self.scrub_remaining.set((versions.len() - *position) as f64);
+                }
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_is_deterministic_and_sensitive_to_content() {
+        assert_eq!(checksum(b"hello"), checksum(b"hello"));
+        assert_ne!(checksum(b"hello"), checksum(b"hellp"));
+    }
+
+    #[test]
+    fn lump_id_round_trips_through_object_version() {
+        let version = ObjectVersion(0x1234_5678);
+        assert_eq!(
+            lump_id_to_object_version(object_version_to_lump_id(version)),
+            version
+        );
+    }
+
+    #[test]
+    fn record_digest_treats_first_scrub_as_baseline() {
+        let mut digests = BTreeMap::new();
+        assert_eq!(
+            record_digest(&mut digests, ObjectVersion(1), checksum(b"payload")),
+            VerifyOutcome::Intact
+        );
+    }
+
+    #[test]
+    fn record_digest_detects_drift_since_last_scrub() {
+        let mut digests = BTreeMap::new();
+        let version = ObjectVersion(1);
+        record_digest(&mut digests, version, checksum(b"payload"));
+        assert_eq!(
+            record_digest(&mut digests, version, checksum(b"payload")),
+            VerifyOutcome::Intact,
+            "unchanged content must not be flagged"
+        );
+        assert_eq!(
+            record_digest(&mut digests, version, checksum(b"corrupted")),
+            VerifyOutcome::Corrupted
+        );
+    }
+
+    // Simulates the scenario this request's fix commits addressed: a sweep
+    // that steps through objects in `step`-sized batches (as
+    // `ScrubContent::poll` does) must surface a corrupted object as soon as
+    // that batch is processed, not only once every object has been swept.
+    // This drives the same `record_digest` decision `ScrubContent::verify`
+    // uses, in batches, without needing a real `DeviceHandle` (which this
+    // tree has no fake/in-memory implementation of to drive `ScrubContent`
+    // end-to-end).
+    #[test]
+    fn corruption_is_observable_before_the_whole_sweep_finishes() {
+        let mut digests = BTreeMap::new();
+        let baseline: Vec<(ObjectVersion, &[u8])> = vec![
+            (ObjectVersion(1), b"a"),
+            (ObjectVersion(2), b"b"),
+            (ObjectVersion(3), b"c"),
+            (ObjectVersion(4), b"d"),
+        ];
+        for (version, bytes) in &baseline {
+            record_digest(&mut digests, *version, checksum(bytes));
+        }
+
+        // Object 2 rots on disk before the next sweep.
+        let rescanned: Vec<(ObjectVersion, &[u8])> = vec![
+            (ObjectVersion(1), b"a"),
+            (ObjectVersion(2), b"ROTTED"),
+            (ObjectVersion(3), b"c"),
+            (ObjectVersion(4), b"d"),
+        ];
+        let step = 2;
+        let first_batch = rescanned.chunks(step).next().unwrap();
+        let corrupted_in_first_batch: Vec<_> = first_batch
+            .iter()
+            .filter(|(version, bytes)| {
+                record_digest(&mut digests, *version, checksum(bytes)) == VerifyOutcome::Corrupted
+            })
+            .map(|(version, _)| *version)
+            .collect();
+
+        assert_eq!(
+            corrupted_in_first_batch,
+            vec![ObjectVersion(2)],
+            "corruption found in the first step-sized batch must be observable \
+             without waiting for the remaining, unprocessed batches"
+        );
+    }
+}
@@ -2,23 +2,37 @@ use cannyls::device::DeviceHandle;
 use fibers::time::timer::{self, Timeout};
 use frugalos_mds::Event;
 use frugalos_raft::NodeId;
-use futures::{Async, Future, Poll};
+use futures::future::{self, Either};
+use futures::sync::{mpsc, oneshot};
+use futures::{Async, Future, Poll, Stream};
 use libfrugalos::entity::object::ObjectVersion;
 use libfrugalos::repair::RepairIdleness;
-use prometrics::metrics::{Counter, Gauge, MetricBuilder};
+use prometrics::metrics::{Counter, Gauge, Histogram, MetricBuilder};
 use slog::Logger;
 use std::cmp::{self, Reverse};
-use std::collections::{BTreeSet, BinaryHeap};
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant, SystemTime};
 
+use checkpoint;
 use client::storage::StorageClient;
 use delete::DeleteContent;
 use full_sync::FullSync;
 use repair::{RepairContent, RepairMetrics};
+use scrub::ScrubContent;
+use token_bucket::{duration_to_secs, TokenBucket};
 use Error;
 
 const MAX_TIMEOUT_SECONDS: u64 = 60;
 const DELETE_CONCURRENCY: usize = 16;
+// How often an automatic scrub sweep is started, absent an on-demand request.
+const DEFAULT_SCRUB_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+// The amount of I/O (in bytes) that a single repair or scrub read is assumed
+// to cost, for the purpose of rate-limiting. Actual object sizes vary, but
+// this is only used to pace background I/O, not to account for it exactly.
+const ESTIMATED_IO_UNIT_BYTES: u64 = 1024 * 1024;
+// How often in-progress sweep progress is checkpointed to the device.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
 
 // TODO: 起動直後の確認は`device.list()`の結果を使った方が効率的
 pub struct Synchronizer {
@@ -42,9 +56,67 @@ pub struct Synchronizer {
     full_sync_remaining: Gauge,
     full_sync: Option<FullSync>,
     full_sync_step: u64,
+    scrub_count: Counter,
+    scrub_corrupted_objects: Counter,
+    scrub_remaining: Gauge,
+    // Whether an (automatic or on-demand) scrub sweep is waiting to be started.
+    scrub_pending: bool,
+    // How often a scrub sweep is started automatically.
+    scrub_interval: Duration,
+    last_scrub_started: Instant,
     // The idleness threshold for repair functionality.
     repair_idleness_threshold: RepairIdleness,
     last_not_idle: Instant,
+    // Rate-limits the I/O performed by repair and scrub so that they cannot
+    // starve foreground traffic. Shared with `ScrubContent`, which consumes
+    // tokens per object it verifies.
+    io_limiter: Arc<Mutex<TokenBucket>>,
+    io_limit_rate: Gauge,
+    // The digest recorded for each object as of its last scrub; shared with
+    // the running `ScrubContent` (if any) and persisted alongside the scrub
+    // checkpoint so that corruption detection survives a restart. See
+    // `checkpoint::{save,load}_digest_catalogue`.
+    object_digests: Arc<Mutex<BTreeMap<ObjectVersion, u64>>>,
+    // Runtime control: lets an operator pause/resume/cancel background work
+    // and query whether this node's synchronizer is busy, without reading
+    // Prometheus scrapes.
+    command_tx: mpsc::UnboundedSender<Command>,
+    command_rx: mpsc::UnboundedReceiver<Command>,
+    paused: bool,
+    // Proportional self-throttling: after each repair/delete/scrub
+    // operation, wait `duration * tranquility` before starting the next
+    // one. `0` (the default) disables this and runs at full speed.
+    tranquility: u32,
+    task_started_at: Instant,
+    task_duration: Histogram,
+    // The boundary of the full sync currently in progress, if any; tracked
+    // here (rather than read back from `full_sync`) purely so it can be
+    // checkpointed. See `checkpoint::FullSyncCheckpoint`.
+    full_sync_next_commit: Option<ObjectVersion>,
+    // A scrub checkpoint loaded at startup, applied to (and then cleared
+    // by) the first scrub sweep started after this. There is no equivalent
+    // `full_sync_resume`: a loaded `FullSyncCheckpoint` is logged for
+    // operator visibility only, since `FullSync` exposes no in-sweep cursor
+    // to resume from (see `checkpoint::FullSyncCheckpoint`'s docs).
+    scrub_resume: Option<checkpoint::ScrubCheckpoint>,
+    // Loads the scrub checkpoint and digest catalogue above (and logs the
+    // FullSync one) off the device without blocking `Synchronizer::new`;
+    // polled to completion from `poll`. `None` once loaded (or once there
+    // was nothing left to load).
+    checkpoint_load: Option<
+        Box<
+            Future<Item = (Option<checkpoint::ScrubCheckpoint>, BTreeMap<ObjectVersion, u64>), Error = Error>
+                + Send,
+        >,
+    >,
+    last_checkpoint: Instant,
+    checkpoint_write: Option<Box<Future<Item = (), Error = Error> + Send>>,
+    // Whether the most recent periodic checkpoint persisted a (non-empty)
+    // scrub/full-sync checkpoint, respectively. Used so an idle node, once
+    // it has cleared a stale checkpoint, stops writing to the device on
+    // every tick.
+    scrub_checkpointed: bool,
+    full_sync_checkpointed: bool,
 }
 impl Synchronizer {
     pub fn new(
@@ -59,6 +131,8 @@ impl Synchronizer {
             .subsystem("synchronizer")
             .label("node", &node_id.to_string())
             .clone();
+        let (command_tx, command_rx) = mpsc::unbounded();
+        let checkpoint_load = Some(Self::load_checkpoints(&logger, &device));
         Synchronizer {
             logger,
             node_id,
@@ -103,8 +177,184 @@ impl Synchronizer {
                 .expect("metric should be well-formed"),
             full_sync: None,
             full_sync_step,
+            scrub_count: metric_builder
+                .counter("scrub_count")
+                .finish()
+                .expect("metric should be well-formed"),
+            scrub_corrupted_objects: metric_builder
+                .counter("scrub_corrupted_objects")
+                .finish()
+                .expect("metric should be well-formed"),
+            scrub_remaining: metric_builder
+                .gauge("scrub_remaining")
+                .finish()
+                .expect("metric should be well-formed"),
+            scrub_pending: false,
+            scrub_interval: DEFAULT_SCRUB_INTERVAL,
+            last_scrub_started: Instant::now(),
             repair_idleness_threshold: RepairIdleness::Disabled, // No repairing happens
             last_not_idle: Instant::now(),
+            io_limiter: Arc::new(Mutex::new(TokenBucket::new(0.0))),
+            io_limit_rate: metric_builder
+                .gauge("io_limit_rate_bytes_per_second")
+                .finish()
+                .expect("metric should be well-formed"),
+            object_digests: Arc::new(Mutex::new(BTreeMap::new())),
+            command_tx,
+            command_rx,
+            paused: false,
+            tranquility: 0,
+            task_started_at: Instant::now(),
+            task_duration: metric_builder
+                .histogram("task_duration_seconds")
+                .bucket(vec![0.001, 0.01, 0.1, 1.0, 10.0, 60.0, 600.0])
+                .finish()
+                .expect("metric should be well-formed"),
+            full_sync_next_commit: None,
+            scrub_resume: None,
+            checkpoint_load,
+            last_checkpoint: Instant::now(),
+            checkpoint_write: None,
+            scrub_checkpointed: false,
+            full_sync_checkpointed: false,
+        }
+    }
+    // Loads the scrub checkpoint and digest catalogue (and logs, but does
+    // not act on, any FullSync checkpoint) off `device` without blocking the
+    // calling thread (unlike a `.wait()` in `new`, which would either
+    // deadlock if `new` runs on the same executor that services the device,
+    // or simply stall startup on device latency). Failures are logged and
+    // treated the same as "nothing found".
+    fn load_checkpoints(
+        logger: &Logger,
+        device: &DeviceHandle,
+    ) -> Box<
+        Future<Item = (Option<checkpoint::ScrubCheckpoint>, BTreeMap<ObjectVersion, u64>), Error = Error>
+            + Send,
+    > {
+        let scrub_logger = logger.clone();
+        let scrub = checkpoint::load_scrub_checkpoint(device).then(
+            move |result| -> Result<Option<checkpoint::ScrubCheckpoint>, Error> {
+                Ok(result.unwrap_or_else(|e| {
+                    warn!(scrub_logger, "Failed to load scrub checkpoint: {}", e);
+                    None
+                }))
+            },
+        );
+        let full_sync_logger = logger.clone();
+        let full_sync = checkpoint::load_full_sync_checkpoint(device).then(
+            move |result| -> Result<(), Error> {
+                // Informational only: `next_commit` is a fixed sweep
+                // boundary, not an in-sweep cursor, so there is nothing
+                // here a restarted FullSync could resume from.
+                match result {
+                    Ok(Some(checkpoint)) => info!(
+                        full_sync_logger,
+                        "Found a FullSync checkpoint from a previous run \
+                         (next_commit={:?}, approximately {} objects remaining); \
+                         FullSync cannot resume from this and will restart its scan",
+                        checkpoint.next_commit,
+                        checkpoint.remaining
+                    ),
+                    Ok(None) => {}
+                    Err(e) => warn!(full_sync_logger, "Failed to load FullSync checkpoint: {}", e),
+                }
+                Ok(())
+            },
+        );
+        let digest_logger = logger.clone();
+        let digests = checkpoint::load_digest_catalogue(device).then(
+            move |result| -> Result<BTreeMap<ObjectVersion, u64>, Error> {
+                Ok(result.unwrap_or_else(|e| {
+                    warn!(digest_logger, "Failed to load digest catalogue: {}", e);
+                    BTreeMap::new()
+                }))
+            },
+        );
+        let result: Box<Future<Item = _, Error = Error> + Send> = Box::new(
+            scrub
+                .join3(full_sync, digests)
+                .map(|(scrub_resume, (), digests)| (scrub_resume, digests)),
+        );
+        result
+    }
+    /// Returns a cheaply-cloneable handle that lets a caller (e.g. an
+    /// admin/CLI endpoint) pause, resume, or cancel this synchronizer's
+    /// background work, or query its current state.
+    pub fn handle(&self) -> SynchronizerHandle {
+        SynchronizerHandle {
+            command_tx: self.command_tx.clone(),
+        }
+    }
+    fn worker_state(&self) -> WorkerState {
+        if self.paused {
+            return WorkerState::Paused;
+        }
+        match self.task {
+            Task::Idle
+                if self.todo_repair.is_empty()
+                    && self.todo_delete.is_empty()
+                    && !self.scrub_pending
+                    && self.full_sync.is_none() =>
+            {
+                WorkerState::Idle
+            }
+            _ => WorkerState::Active,
+        }
+    }
+    fn handle_commands(&mut self) {
+        while let Ok(Async::Ready(Some(command))) = self.command_rx.poll() {
+            match command {
+                Command::Pause => {
+                    info!(self.logger, "Synchronizer paused");
+                    self.paused = true;
+                }
+                Command::Resume => {
+                    info!(self.logger, "Synchronizer resumed");
+                    self.paused = false;
+                }
+                Command::CancelFullSync => {
+                    info!(self.logger, "FullSync cancelled on request");
+                    self.full_sync = None;
+                    self.full_sync_next_commit = None;
+                    self.full_sync_remaining.set(0.0);
+                }
+                Command::CancelScrub => {
+                    self.scrub_pending = false;
+                    // Flush whatever corruption the sweep had already found
+                    // but not yet reported before dropping it, so cancelling
+                    // a scrub never silently discards detected-but-unrepaired
+                    // corruption. In practice the per-tick drain in `poll` should
+                    // already have reported all of it, but flush here too in
+                    // case a `CancelScrub` and a pending discovery land in
+                    // the same tick.
+                    let pending_corruption = if let Task::Scrub(ref mut scrub) = self.task {
+                        scrub.take_corrupted_objects()
+                    } else {
+                        Vec::new()
+                    };
+                    if let Task::Scrub(_) = self.task {
+                        self.task = Task::Idle;
+                    }
+                    if pending_corruption.is_empty() {
+                        info!(self.logger, "Scrub cancelled on request");
+                    } else {
+                        warn!(
+                            self.logger,
+                            "Scrub cancelled on request with {} corrupted object(s) not yet \
+                             repaired; enqueuing repairs for them before dropping the sweep",
+                            pending_corruption.len()
+                        );
+                    }
+                    for version in pending_corruption {
+                        self.enqueue_repair(version);
+                    }
+                    self.scrub_remaining.set(0.0);
+                }
+                Command::QueryState(reply) => {
+                    let _ = reply.send(self.worker_state());
+                }
+            }
         }
     }
     pub fn handle_event(&mut self, event: &Event) {
@@ -123,6 +373,11 @@ impl Synchronizer {
                 }
                 Event::Deleted { version } => {
                     self.repair_candidates.remove(&version);
+                    // Otherwise the digest catalogue only ever grows: it
+                    // would keep an entry per distinct object ever scrubbed,
+                    // including ones deleted long ago and never revisited by
+                    // a scrub sweep since.
+                    self.object_digests.lock().unwrap().remove(&version);
                     if let Some(mut head) = self.todo_delete.peek_mut() {
                         if let TodoItem::DeleteContent { ref mut versions } = head.0 {
                             if versions.len() < DELETE_CONCURRENCY {
@@ -140,18 +395,31 @@ impl Synchronizer {
                     next_commit,
                 } => {
                     // If FullSync is not being processed now, this event lets the synchronizer to handle one.
-                    if self.full_sync.is_none() {
+                    // While paused, don't start one either: `poll` won't drive it until `Resume`
+                    // anyway (see the full_sync poll loop below), so starting it now would just
+                    // mean silently doing work the operator asked to hold off on.
+                    if self.full_sync.is_none() && !self.paused {
+                        let next_commit = ObjectVersion(next_commit.as_u64());
+                        // NOTE: a `FullSyncCheckpoint` loaded at startup is deliberately not
+                        // applied here. `next_commit` is the sweep's fixed boundary, not an
+                        // in-sweep scan cursor, and `FullSync` exposes no way to skip objects
+                        // already covered by an interrupted sweep — see
+                        // `checkpoint::FullSyncCheckpoint`'s docs. Bumping `next_commit` to a checkpointed value would
+                        // only change which objects the sweep covers, not make it resume, so
+                        // FullSync always restarts its scan from the beginning; only the
+                        // scrub sweep below actually resumes.
                         self.full_sync = Some(FullSync::new(
                             &self.logger,
                             self.node_id,
                             &self.device,
                             machine.clone(),
-                            ObjectVersion(next_commit.as_u64()),
+                            next_commit,
                             self.full_sync_count.clone(),
                             self.full_sync_deleted_objects.clone(),
                             self.full_sync_remaining.clone(),
                             self.full_sync_step,
                         ));
+                        self.full_sync_next_commit = Some(next_commit);
                     }
                 }
             }
@@ -164,6 +432,11 @@ impl Synchronizer {
         }
     }
     fn next_todo_item(&mut self) -> Option<TodoItem> {
+        if self.paused {
+            // In-flight work (the current `self.task`) is left to finish,
+            // but no new task is started while paused.
+            return None;
+        }
         let item = loop {
             // Repair has priority higher than deletion. If repair is enabled, todo_repair should be examined first.
             let maybe_item = if self.is_repair_enabled() {
@@ -175,6 +448,16 @@ impl Synchronizer {
             } else {
                 self.todo_delete.pop()
             };
+            // Scrub is the lowest priority background work: it only starts
+            // once there is nothing more pressing to do.
+            let maybe_item = maybe_item.or_else(|| {
+                if self.scrub_pending {
+                    self.scrub_pending = false;
+                    Some(Reverse(TodoItem::Scrub))
+                } else {
+                    None
+                }
+            });
             if let Some(item) = maybe_item {
                 if let TodoItem::RepairContent { version, .. } = item.0 {
                     if !self.repair_candidates.contains(&version) {
@@ -230,24 +513,181 @@ impl Synchronizer {
         );
         self.repair_idleness_threshold = repair_idleness_threshold;
     }
+    pub(crate) fn set_tranquility(&mut self, tranquility: u32) {
+        info!(self.logger, "tranquility set to {:?}", tranquility);
+        self.tranquility = tranquility;
+    }
+    // Records how long the just-finished task took and, if `tranquility` is
+    // non-zero, returns how long to wait before starting the next one.
+    // Returns `None` for `Task::Idle`/`Task::Wait`, which aren't measured.
+    fn throttle_after_task(&mut self) -> Option<Duration> {
+        match self.task {
+            Task::Repair(_) | Task::Delete(_) | Task::Scrub(_) => {}
+            Task::Idle | Task::Wait(_) => return None,
+        }
+        let elapsed = self.task_started_at.elapsed();
+        self.task_duration.observe(duration_to_secs(elapsed));
+        if self.tranquility == 0 {
+            return None;
+        }
+        let wait = elapsed * self.tranquility;
+        if wait == Duration::from_secs(0) {
+            None
+        } else {
+            Some(wait)
+        }
+    }
     fn is_repair_enabled(&self) -> bool {
         match self.repair_idleness_threshold {
             RepairIdleness::Threshold(_) => true,
             RepairIdleness::Disabled => false,
         }
     }
+    pub(crate) fn set_scrub_interval(&mut self, scrub_interval: Duration) {
+        info!(self.logger, "scrub_interval set to {:?}", scrub_interval);
+        self.scrub_interval = scrub_interval;
+    }
+    /// Sets the I/O rate limit (bytes/sec) applied to repair and scrub
+    /// reads. `None` (or a rate of `0`) disables limiting.
+    pub(crate) fn set_io_limit(&mut self, bytes_per_second: Option<u64>) {
+        let rate = bytes_per_second.unwrap_or(0) as f64;
+        info!(self.logger, "io_limit set to {:?} bytes/sec", rate);
+        self.io_limiter.lock().unwrap().set_rate(rate);
+        self.io_limit_rate.set(rate);
+    }
+    /// Requests that a scrub sweep be started as soon as the synchronizer is
+    /// free to do so, regardless of `scrub_interval`.
+    pub fn start_scrub(&mut self) {
+        info!(self.logger, "Scrub requested on demand");
+        self.scrub_pending = true;
+    }
+    // Kicks off (non-blocking) persistence of the current sweep progress, if
+    // the previous checkpoint write has finished and enough time has passed.
+    //
+    // An idle node (no scrub running, no full sync in progress, and nothing
+    // left over to clear from a previous sweep) does nothing here at all:
+    // without that, this would issue a `clear` write to the device on every
+    // tick forever.
+    fn maybe_checkpoint(&mut self) {
+        if self.checkpoint_write.is_some() || self.last_checkpoint.elapsed() < CHECKPOINT_INTERVAL
+        {
+            return;
+        }
+        let scrub_active = match self.task {
+            Task::Scrub(_) => true,
+            _ => false,
+        };
+        let full_sync_active = self.full_sync_next_commit.is_some();
+        let scrub_needs_write = scrub_active || self.scrub_checkpointed;
+        let full_sync_needs_write = full_sync_active || self.full_sync_checkpointed;
+        if !scrub_needs_write && !full_sync_needs_write {
+            return;
+        }
+        self.last_checkpoint = Instant::now();
+        let device = self.device.clone();
+        let scrub_write: Box<Future<Item = (), Error = Error> + Send> = if scrub_active {
+            self.scrub_checkpointed = true;
+            if let Task::Scrub(ref scrub) = self.task {
+                Box::new(checkpoint::save_scrub_checkpoint(&device, scrub.checkpoint()))
+            } else {
+                unreachable!()
+            }
+        } else if self.scrub_checkpointed {
+            self.scrub_checkpointed = false;
+            Box::new(checkpoint::clear_scrub_checkpoint(&device))
+        } else {
+            Box::new(future::ok(()))
+        };
+        let full_sync_write: Box<Future<Item = (), Error = Error> + Send> = if full_sync_active {
+            self.full_sync_checkpointed = true;
+            let checkpoint = checkpoint::FullSyncCheckpoint {
+                next_commit: self.full_sync_next_commit.expect("checked above"),
+                remaining: self.full_sync_remaining.value() as u64,
+            };
+            Box::new(checkpoint::save_full_sync_checkpoint(&device, checkpoint))
+        } else if self.full_sync_checkpointed {
+            self.full_sync_checkpointed = false;
+            Box::new(checkpoint::clear_full_sync_checkpoint(&device))
+        } else {
+            Box::new(future::ok(()))
+        };
+        // Unlike the scrub/full-sync checkpoints, the digest catalogue is
+        // never cleared: it is a durable record of what each object looked
+        // like as of its last scrub, not in-progress sweep state, so it
+        // stays useful across sweeps (entries for deleted objects are
+        // evicted as they're noticed, in `handle_event` and `scrub::verify`,
+        // rather than here). It is only worth writing while a scrub is
+        // actually updating it.
+        //
+        // Unlike the fixed-size scrub/full-sync checkpoints, this write can
+        // be arbitrarily large (one entry per distinct object ever
+        // scrubbed), so it is charged against `io_limiter` like any other
+        // background I/O; if there isn't enough budget right now, the write
+        // is simply skipped and retried at the next checkpoint tick rather
+        // than forced through and starving foreground traffic.
+        let digest_write: Box<Future<Item = (), Error = Error> + Send> = if scrub_active {
+            let digests = self.object_digests.lock().unwrap().clone();
+            let estimated_bytes = (8 + digests.len() * 16) as u64;
+            if self
+                .io_limiter
+                .lock()
+                .unwrap()
+                .try_consume(estimated_bytes)
+                .is_none()
+            {
+                Box::new(checkpoint::save_digest_catalogue(&device, &digests))
+            } else {
+                Box::new(future::ok(()))
+            }
+        } else {
+            Box::new(future::ok(()))
+        };
+        self.checkpoint_write = Some(Box::new(
+            scrub_write
+                .join3(full_sync_write, digest_write)
+                .map(|_| ()),
+        ));
+    }
+    fn enqueue_repair(&mut self, version: ObjectVersion) {
+        self.enqueued_repair.increment();
+        self.repair_candidates.insert(version);
+        self.todo_repair.push(Reverse(TodoItem::RepairContent {
+            start_time: SystemTime::now(),
+            version,
+        }));
+    }
 }
 impl Future for Synchronizer {
     type Item = ();
     type Error = Error;
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        while let Async::Ready(Some(())) = self.full_sync.poll().unwrap_or_else(|e| {
-            warn!(self.logger, "Task failure: {}", e);
-            Async::Ready(Some(()))
-        }) {
-            // Full sync is done. Clearing the full_sync field.
-            self.full_sync = None;
-            self.full_sync_remaining.set(0.0);
+        self.handle_commands();
+
+        if let Some(mut load) = self.checkpoint_load.take() {
+            match load.poll() {
+                Ok(Async::Ready((scrub_resume, digests))) => {
+                    self.scrub_resume = scrub_resume;
+                    *self.object_digests.lock().unwrap() = digests;
+                }
+                Ok(Async::NotReady) => self.checkpoint_load = Some(load),
+                Err(e) => warn!(self.logger, "Failed to load startup checkpoints: {}", e),
+            }
+        }
+
+        // `Pause` covers FullSync too: while paused, an in-flight sweep is
+        // simply not polled (so it performs no further I/O), and the branch
+        // below that would otherwise start one in response to `Event::FullSync`
+        // does nothing until `Resume`. See `Command::Pause`'s doc comment.
+        if !self.paused {
+            while let Async::Ready(Some(())) = self.full_sync.poll().unwrap_or_else(|e| {
+                warn!(self.logger, "Task failure: {}", e);
+                Async::Ready(Some(()))
+            }) {
+                // Full sync is done. Clearing the full_sync field.
+                self.full_sync = None;
+                self.full_sync_next_commit = None;
+                self.full_sync_remaining.set(0.0);
+            }
         }
 
         if !self.task.is_sleeping() {
@@ -255,17 +695,57 @@ impl Future for Synchronizer {
             debug!(self.logger, "last_not_idle = {:?}", self.last_not_idle);
         }
 
-        while let Async::Ready(()) = self.task.poll().unwrap_or_else(|e| {
-            // 同期処理のエラーは致命的ではないので、ログを出すだけに留める
-            warn!(self.logger, "Task failure: {}", e);
-            Async::Ready(())
-        }) {
+        if !self.scrub_pending && self.last_scrub_started.elapsed() >= self.scrub_interval {
+            info!(self.logger, "Starting an automatic scrub sweep");
+            self.scrub_pending = true;
+        }
+
+        self.maybe_checkpoint();
+        if let Some(write) = self.checkpoint_write.as_mut() {
+            match write.poll() {
+                Ok(Async::Ready(())) => self.checkpoint_write = None,
+                Ok(Async::NotReady) => {}
+                Err(e) => {
+                    warn!(self.logger, "Failed to persist sweep checkpoint: {}", e);
+                    self.checkpoint_write = None;
+                }
+            }
+        }
+
+        loop {
+            let task_poll = self.task.poll().unwrap_or_else(|e| {
+                // 同期処理のエラーは致命的ではないので、ログを出すだけに留める
+                warn!(self.logger, "Task failure: {}", e);
+                Async::Ready(())
+            });
+            // Drained every tick, not only once the sweep as a whole
+            // completes: a sweep over a large device can take hours, and
+            // `ScrubContent::poll` only resolves `Ready` at the very end, so
+            // waiting for that would leave corrupted objects unrepaired (and
+            // lost entirely on cancel/restart) for the sweep's whole
+            // lifetime. See `ScrubContent::take_corrupted_objects`.
+            if let Task::Scrub(ref mut scrub) = self.task {
+                let corrupted = scrub.take_corrupted_objects();
+                for version in corrupted {
+                    self.enqueue_repair(version);
+                }
+            }
+            match task_poll {
+                Async::NotReady => break,
+                Async::Ready(()) => {}
+            }
+            let tranquility_wait = self.throttle_after_task();
             self.task = Task::Idle;
+            if let Some(wait) = tranquility_wait {
+                self.task = Task::Wait(timer::timeout(wait));
+                continue;
+            }
             if let Some(item) = self.next_todo_item() {
                 match item {
                     TodoItem::DeleteContent { versions } => {
                         self.dequeued_delete.increment();
                         self.task = Task::Delete(DeleteContent::new(self, versions));
+                        self.task_started_at = Instant::now();
                         self.last_not_idle = Instant::now();
                     }
                     TodoItem::RepairContent { version, .. } => {
@@ -277,13 +757,41 @@ impl Future for Synchronizer {
                                 self.repair_candidates.insert(version);
                                 self.todo_repair.push(Reverse(item));
                                 break;
+                            } else if let Some(wait) = self
+                                .io_limiter
+                                .lock()
+                                .unwrap()
+                                .try_consume(ESTIMATED_IO_UNIT_BYTES)
+                            {
+                                self.repair_candidates.insert(version);
+                                self.todo_repair.push(Reverse(item));
+                                self.task = Task::Wait(timer::timeout(wait));
+                                break;
                             } else {
                                 self.dequeued_repair.increment();
                                 self.task = Task::Repair(RepairContent::new(self, version));
+                                self.task_started_at = Instant::now();
                                 self.last_not_idle = Instant::now();
                             }
                         }
                     }
+                    TodoItem::Scrub => {
+                        self.last_scrub_started = Instant::now();
+                        self.task = Task::Scrub(ScrubContent::new(
+                            &self.logger,
+                            self.node_id,
+                            &self.device,
+                            self.scrub_count.clone(),
+                            self.scrub_corrupted_objects.clone(),
+                            self.scrub_remaining.clone(),
+                            self.full_sync_step,
+                            Arc::clone(&self.io_limiter),
+                            self.scrub_resume.take(),
+                            Arc::clone(&self.object_digests),
+                        ));
+                        self.task_started_at = Instant::now();
+                        self.last_not_idle = Instant::now();
+                    }
                 }
             } else if let Task::Idle = self.task {
                 break;
@@ -302,6 +810,10 @@ enum TodoItem {
     DeleteContent {
         versions: Vec<ObjectVersion>,
     },
+    // Triggers a background scrub sweep. Carries no data: a single sweep
+    // covers every object on the device, so there is never more than one
+    // pending at a time.
+    Scrub,
 }
 impl TodoItem {
     pub fn new(event: &Event) -> Self {
@@ -324,7 +836,7 @@ impl TodoItem {
     }
     pub fn wait_time(&self) -> Option<Duration> {
         match *self {
-            TodoItem::DeleteContent { .. } => None,
+            TodoItem::DeleteContent { .. } | TodoItem::Scrub => None,
             TodoItem::RepairContent { start_time, .. } => {
                 start_time.duration_since(SystemTime::now()).ok()
             }
@@ -338,6 +850,7 @@ enum Task {
     Wait(Timeout),
     Delete(DeleteContent),
     Repair(RepairContent),
+    Scrub(ScrubContent),
 }
 impl Task {
     fn is_sleeping(&self) -> bool {
@@ -357,6 +870,83 @@ impl Future for Task {
             Task::Wait(ref mut f) => track!(f.poll().map_err(Error::from)),
             Task::Delete(ref mut f) => track!(f.poll()),
             Task::Repair(ref mut f) => track!(f.poll()),
+            Task::Scrub(ref mut f) => track!(f.poll()),
+        }
+    }
+}
+
+/// A command accepted by a running `Synchronizer`, sent through the channel
+/// returned by `Synchronizer::handle`.
+enum Command {
+    /// Stops new repair/delete/scrub work from being started (in-flight work
+    /// finishes), and additionally stops `FullSync` outright: a running sweep
+    /// is not polled (so it does no further I/O) and `Event::FullSync` does
+    /// not start a new one, until `Resume`.
+    Pause,
+    /// Reverses `Pause`.
+    Resume,
+    /// Cancels the current `FullSync` sweep, if any, making it restart from
+    /// scratch on the next `Event::FullSync`.
+    CancelFullSync,
+    /// Cancels the current scrub sweep, if any.
+    CancelScrub,
+    /// Asks for a snapshot of the synchronizer's current state.
+    QueryState(oneshot::Sender<WorkerState>),
+}
+
+/// A snapshot of what a `Synchronizer` is doing, as observed by `QueryState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerState {
+    /// Running a task (repair, delete, full sync, or scrub), waiting before
+    /// the next one, or holding queued or pending work.
+    Active,
+    /// No work queued and nothing in flight.
+    Idle,
+    /// Paused by a `Pause` command; not starting new work.
+    Paused,
+    /// The synchronizer's command channel is gone (it has shut down).
+    Dead,
+}
+
+/// A cheaply-cloneable handle to a running `Synchronizer`'s control channel.
+///
+/// This lets an operator (e.g. an admin endpoint or CLI) pause background
+/// rebuilds during an incident, or check whether a node's synchronizer is
+/// busy, idle, or stuck.
+#[derive(Clone)]
+pub struct SynchronizerHandle {
+    command_tx: mpsc::UnboundedSender<Command>,
+}
+impl SynchronizerHandle {
+    /// Stops new repair/delete/scrub work from being started (in-flight work
+    /// finishes), and also halts `FullSync`: an in-progress sweep stops
+    /// making progress and no new one is started, until `resume`.
+    pub fn pause(&self) {
+        let _ = self.command_tx.unbounded_send(Command::Pause);
+    }
+    /// Reverses `pause`.
+    pub fn resume(&self) {
+        let _ = self.command_tx.unbounded_send(Command::Resume);
+    }
+    /// Cancels the current `FullSync` sweep, if any.
+    pub fn cancel_full_sync(&self) {
+        let _ = self.command_tx.unbounded_send(Command::CancelFullSync);
+    }
+    /// Cancels the current scrub sweep, if any.
+    pub fn cancel_scrub(&self) {
+        let _ = self.command_tx.unbounded_send(Command::CancelScrub);
+    }
+    /// Asks the synchronizer for a snapshot of its current state. Resolves
+    /// to `WorkerState::Dead` if the synchronizer has already shut down.
+    pub fn query_state(&self) -> impl Future<Item = WorkerState, Error = ()> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if self
+            .command_tx
+            .unbounded_send(Command::QueryState(reply_tx))
+            .is_err()
+        {
+            return Either::A(future::ok(WorkerState::Dead));
         }
+        Either::B(reply_rx.or_else(|_canceled| Ok(WorkerState::Dead)))
     }
 }
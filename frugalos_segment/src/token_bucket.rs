@@ -0,0 +1,122 @@
+//! A small token bucket used to cap the I/O rate of background operations
+//! (repair, scrub) so that they do not starve foreground traffic.
+use std::time::{Duration, Instant};
+
+/// Converts a `Duration` into a fractional number of seconds.
+pub(crate) fn duration_to_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1_000_000_000.0
+}
+
+fn secs_to_duration(secs: f64) -> Duration {
+    let secs = secs.max(0.0);
+    let whole = secs.trunc();
+    let nanos = (secs.fract() * 1_000_000_000.0) as u32;
+    Duration::new(whole as u64, nanos)
+}
+
+/// Gates access to a shared, rate-limited resource measured in bytes per
+/// second.
+///
+/// A `rate` of `0.0` disables limiting entirely: `try_consume` always
+/// succeeds without ever depleting `available`.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    available: f64,
+    rate: f64,
+    last_refill: Instant,
+}
+impl TokenBucket {
+    /// Creates a bucket with the given `rate` (bytes/sec). The burst
+    /// capacity defaults to one second worth of tokens at that rate.
+    pub fn new(rate: f64) -> Self {
+        TokenBucket {
+            capacity: rate,
+            available: rate,
+            rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// The currently configured rate, in bytes/sec (`0.0` if disabled).
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Updates the rate (and the burst capacity, which tracks it) at
+    /// runtime. Passing `0.0` disables limiting.
+    pub fn set_rate(&mut self, rate: f64) {
+        let rate = rate.max(0.0);
+        self.capacity = rate;
+        self.available = self.available.min(self.capacity);
+        self.rate = rate;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = duration_to_secs(now.duration_since(self.last_refill));
+        self.available = (self.available + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    /// Attempts to withdraw `bytes` tokens.
+    ///
+    /// Returns `None` if the withdrawal succeeded (the caller may proceed
+    /// immediately). Returns `Some(wait)` if there were not enough tokens;
+    /// in that case no tokens are withdrawn, and the caller should retry no
+    /// sooner than `wait` from now.
+    pub fn try_consume(&mut self, bytes: u64) -> Option<Duration> {
+        if self.rate <= 0.0 {
+            return None;
+        }
+        self.refill();
+        // A single caller-side unit of I/O (e.g. a 1 MiB scrub read) can
+        // exceed the bucket's own capacity (a 512 KiB/s limit has a 512 KiB
+        // capacity); without this, `available` could never reach `bytes` and
+        // `try_consume` would report a wait forever. Clamping what's charged
+        // to `capacity` means `available` is always eventually large enough,
+        // at the cost of under-counting the true size of oversized reads.
+        let bytes = (bytes as f64).min(self.capacity);
+        if self.available >= bytes {
+            self.available -= bytes;
+            None
+        } else {
+            let deficit = bytes - self.available;
+            Some(secs_to_duration(deficit / self.rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn sub_unit_rate_still_makes_progress() {
+        // A rate far below a single oversized unit must still eventually let
+        // that unit through, rather than waiting forever because `available`
+        // could never reach an uncapped `bytes`.
+        let mut bucket = TokenBucket::new(1024.0); // 1 KiB/sec, capacity 1 KiB
+        let unit = 1024 * 1024; // 1 MiB, far larger than the bucket's capacity
+        assert_eq!(
+            bucket.try_consume(unit),
+            None,
+            "the initial burst should cover one oversized unit"
+        );
+        let wait = bucket
+            .try_consume(unit)
+            .expect("second attempt should report a wait, not stall forever");
+        assert!(
+            wait <= Duration::from_secs(2),
+            "wait should scale with capacity, not the oversized unit: {:?}",
+            wait
+        );
+        thread::sleep(wait + Duration::from_millis(50));
+        assert_eq!(
+            bucket.try_consume(unit),
+            None,
+            "after waiting out the reported delay, the unit should be consumable"
+        );
+    }
+}